@@ -0,0 +1,288 @@
+use kalosm_sample::{ChoiceParser, CreateParserState, Either, LiteralParser, Parser, SequenceParser};
+use serde_json::Value;
+
+use super::{ArgumentParser, ArgumentSchema, LiteralSetParser, OneLine, ToolManager};
+
+/// A single step of the agent loop, independent of how it was rendered or parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentStep {
+    /// A freeform thought, with no tool call yet
+    Thought(String),
+    /// A call into one of the manager's tools by index. The argument payload is not captured
+    /// here; it hasn't been generated yet. Follow up with [`ToolManager::input_constraints`] or
+    /// [`ToolManager::tool_argument_constraints`] for the tool picked here to constrain the next
+    /// round of generation.
+    Action {
+        /// The index of the chosen tool
+        tool_index: usize,
+    },
+    /// The final answer to the original question
+    Answer(String),
+}
+
+/// A pluggable way to render an agent prompt and constrain a single step of generation, so
+/// `ToolManager` isn't limited to the textual ReAct scheme (`Thought:`/`Action:`/`Input:`/
+/// `Final Answer:`). Ship [`ReActFormat`] is the default; [`JsonFunctionFormat`] targets chat
+/// models trained on structured function-calling instead.
+pub trait AgentFormat: Sized {
+    /// The parser produced for a single step of generation
+    type StepParser: Parser<Output = AgentStep> + CreateParserState + Send + Sync + 'static;
+
+    /// Render the prompt for a question, given the manager's tools
+    fn prompt(&self, tools: &ToolManager<Self>, question: impl std::fmt::Display) -> String;
+
+    /// Build the parser that constrains one step of generation: a thought, a tool selection, or
+    /// a final answer
+    fn step_constraints(&self, tools: &ToolManager<Self>) -> Self::StepParser;
+}
+
+/// A [`Parser`] that applies a plain function to the output of an inner parser. Used to adapt the
+/// ReAct and JSON function-calling grammars onto the shared [`AgentStep`] output without
+/// duplicating their parsing logic.
+pub struct MapToStep<P: Parser> {
+    parser: P,
+    map: fn(P::Output) -> AgentStep,
+}
+
+impl<P: Parser> Parser for MapToStep<P> {
+    type Error = P::Error;
+    type Output = AgentStep;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> Result<kalosm_sample::ParseResult<'a, Self::PartialState, Self::Output>, Self::Error>
+    {
+        match self.parser.parse(state, input)? {
+            kalosm_sample::ParseResult::Finished { result, remaining } => {
+                Ok(kalosm_sample::ParseResult::Finished {
+                    result: (self.map)(result),
+                    remaining,
+                })
+            }
+            kalosm_sample::ParseResult::Incomplete {
+                new_state,
+                required_next,
+            } => Ok(kalosm_sample::ParseResult::Incomplete {
+                new_state,
+                required_next,
+            }),
+        }
+    }
+}
+
+impl<P: Parser + CreateParserState> CreateParserState for MapToStep<P> {
+    fn create_parser_state(&self) -> Self::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+/// The classic textual ReAct scheme: `Thought:`/`Action:`/`Input:`/`Final Answer:`. This is the
+/// default [`AgentFormat`], matching the prompt and parsers `ToolManager` has always used.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReActFormat;
+
+fn map_react_thought(result: ((), String)) -> AgentStep {
+    AgentStep::Thought(result.1)
+}
+
+fn map_react_action(result: ((), usize)) -> AgentStep {
+    AgentStep::Action { tool_index: result.1 }
+}
+
+fn map_react_answer(result: ((), String)) -> AgentStep {
+    AgentStep::Answer(result.1)
+}
+
+fn map_react_any(
+    result: Either<Either<((), String), ((), usize)>, ((), String)>,
+) -> AgentStep {
+    match result {
+        Either::Left(Either::Left(thought)) => map_react_thought(thought),
+        Either::Left(Either::Right(action)) => map_react_action(action),
+        Either::Right(answer) => map_react_answer(answer),
+    }
+}
+
+impl AgentFormat for ReActFormat {
+    type StepParser = MapToStep<
+        ChoiceParser<
+            ChoiceParser<
+                SequenceParser<LiteralParser<&'static str>, OneLine>,
+                SequenceParser<LiteralParser<&'static str>, LiteralSetParser>,
+            >,
+            SequenceParser<LiteralParser<&'static str>, OneLine>,
+        >,
+    >;
+
+    fn prompt(&self, tools: &ToolManager<Self>, question: impl std::fmt::Display) -> String {
+        tools.react_prompt(question)
+    }
+
+    fn step_constraints(&self, tools: &ToolManager<Self>) -> Self::StepParser {
+        // Built directly from the same literals `ToolManager::{thought,action,answer}_constraints`
+        // use, rather than calling those methods: their return types are opaque (`impl Parser`),
+        // so they can't be named in `Self::StepParser` above.
+        let thought = LiteralParser::from("Thought: ").then(OneLine);
+        let choices = tools
+            .get_tools()
+            .iter()
+            .map(|tool| format!("{}\n{}", tool.name(), tool.input_prompt()));
+        let action = LiteralParser::from("Action: ").then(LiteralSetParser::new(choices));
+        let answer = LiteralParser::from("Final Answer: ").then(OneLine);
+        MapToStep {
+            parser: thought.or(action).or(answer),
+            map: map_react_any,
+        }
+    }
+}
+
+/// A JSON function-calling format: a step is `{"tool": "<name>", "args": <arguments>}` or
+/// `{"final_answer": "<text>"}`, with the tool name constrained to one of the manager's tools by
+/// a [`LiteralSetParser`]. Like [`ReActFormat::step_constraints`], the argument value for a
+/// chosen tool is not part of this grammar (it depends on which tool was picked); follow up with
+/// [`ToolManager::tool_argument_constraints`], which compiles the chosen tool's argument schema
+/// into its own parser, then [`JsonFunctionFormat::closing_constraint`] to close the object this
+/// format wraps the call in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonFunctionFormat;
+
+impl JsonFunctionFormat {
+    /// The literal that closes this format's wrapping object once a tool's arguments have been
+    /// parsed via [`ToolManager::tool_argument_constraints`]: the grammar up to that point only
+    /// constrains `{"tool": "<name>", "args": <value>`, which isn't valid JSON without this `}`.
+    pub fn closing_constraint() -> LiteralParser<&'static str> {
+        LiteralParser::from("}")
+    }
+}
+
+fn map_json_action(result: (((), usize), ())) -> AgentStep {
+    AgentStep::Action {
+        tool_index: (result.0).1,
+    }
+}
+
+fn map_json_answer(result: (((), Value), ())) -> AgentStep {
+    match (result.0).1 {
+        Value::String(answer) => AgentStep::Answer(answer),
+        // `ArgumentSchema::String` only ever parses to `Value::String`
+        _ => unreachable!(),
+    }
+}
+
+fn map_json_any(result: Either<(((), usize), ()), (((), Value), ())>) -> AgentStep {
+    match result {
+        Either::Left(action) => map_json_action(action),
+        Either::Right(answer) => map_json_answer(answer),
+    }
+}
+
+type JsonActionParser = SequenceParser<
+    SequenceParser<LiteralParser<&'static str>, LiteralSetParser>,
+    LiteralParser<&'static str>,
+>;
+
+type JsonAnswerParser = SequenceParser<
+    SequenceParser<LiteralParser<&'static str>, ArgumentParser>,
+    LiteralParser<&'static str>,
+>;
+
+// The closing quote and "args" key are the same regardless of which tool matched, so they're
+// forced right after the `LiteralSetParser` rather than baked into its choices (unlike
+// `ReActFormat`, where the per-tool suffix actually varies).
+fn json_action_parser(names: impl IntoIterator<Item = impl Into<String>>) -> JsonActionParser {
+    LiteralParser::from("{\"tool\": \"")
+        .then(LiteralSetParser::new(names))
+        .then(LiteralParser::from("\", \"args\": "))
+}
+
+// Reuses `ArgumentSchema::String`'s parser (rather than `OneLine`, which passes bytes through
+// unescaped and has no notion of a closing quote) so the answer text is escaped like any other
+// JSON string and the grammar can tell where it ends.
+fn json_answer_parser() -> JsonAnswerParser {
+    LiteralParser::from("{\"final_answer\": ")
+        .then(ArgumentParser::new(ArgumentSchema::String))
+        .then(LiteralParser::from("}"))
+}
+
+impl AgentFormat for JsonFunctionFormat {
+    type StepParser = MapToStep<ChoiceParser<JsonActionParser, JsonAnswerParser>>;
+
+    fn prompt(&self, tools: &ToolManager<Self>, question: impl std::fmt::Display) -> String {
+        let mut functions = String::new();
+        for tool in tools.get_tools() {
+            functions.push_str(&format!("# {}\n{}\n\n", tool.name(), tool.description()));
+        }
+        format!(
+            r#"Answer the question by calling one of the following functions:
+
+{functions}
+Respond with a single JSON object, either {{"tool": "<name>", "args": <arguments>}} to call a
+function or {{"final_answer": "<answer>"}} once you know the answer.
+
+Question: {question}
+"#
+        )
+    }
+
+    fn step_constraints(&self, tools: &ToolManager<Self>) -> Self::StepParser {
+        let names = tools.get_tools().iter().map(|tool| tool.name());
+        MapToStep {
+            parser: json_action_parser(names).or(json_answer_parser()),
+            map: map_json_any,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kalosm_sample::ParseResult;
+
+    fn finish<P>(parser: &P, input: &[u8]) -> (P::Output, usize)
+    where
+        P: Parser + CreateParserState,
+        P::Error: std::fmt::Debug,
+    {
+        let state = parser.create_parser_state();
+        match parser.parse(&state, input).unwrap() {
+            ParseResult::Finished { result, remaining } => (result, remaining.len()),
+            ParseResult::Incomplete { .. } => panic!("expected a finished parse"),
+        }
+    }
+
+    #[test]
+    fn json_action_stops_right_after_the_args_key() {
+        let parser = json_action_parser(["search", "lookup"]);
+        let (result, remaining) = finish(&parser, b"{\"tool\": \"search\", \"args\": {}");
+        assert_eq!(map_json_action(result), AgentStep::Action { tool_index: 0 });
+        // "{}" is left unconsumed for `ToolManager::tool_argument_constraints` to parse
+        assert_eq!(remaining, 2);
+    }
+
+    #[test]
+    fn json_answer_closes_the_object() {
+        let parser = json_answer_parser();
+        let (result, remaining) = finish(&parser, b"{\"final_answer\": \"Paris\"}");
+        assert_eq!(
+            map_json_answer(result),
+            AgentStep::Answer("Paris".to_string())
+        );
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn json_any_dispatches_to_the_matching_arm() {
+        let parser = json_action_parser(["search"]).or(json_answer_parser());
+        let (result, _) = finish(&parser, b"{\"final_answer\": \"Paris\"}");
+        assert_eq!(map_json_any(result), AgentStep::Answer("Paris".to_string()));
+    }
+
+    #[test]
+    fn closing_constraint_forces_the_final_brace() {
+        let (_, remaining) = finish(&JsonFunctionFormat::closing_constraint(), b"}");
+        assert_eq!(remaining, 0);
+    }
+}
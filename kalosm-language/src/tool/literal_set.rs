@@ -0,0 +1,290 @@
+use std::borrow::Cow;
+
+use kalosm_sample::{CreateParserState, ParseResult, Parser};
+
+use super::ParseError;
+
+/// A node of the radix trie backing [`LiteralSetParser`]. Each edge stores the full byte run
+/// shared by every literal that passes through it, so a choice between many literals with a
+/// common prefix (e.g. several tool names sharing a namespace) collapses to a single forced
+/// continuation instead of walking every literal byte by byte.
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    /// Outgoing edges, each labelled with the bytes consumed along it and the node it leads to
+    children: Vec<(Vec<u8>, usize)>,
+    /// The index of the literal that terminates at this node, if any
+    terminal: Option<usize>,
+}
+
+/// A parser that matches one of a set of literal byte strings, backed by a radix trie built
+/// once at construction. When one literal is a prefix of another, the longer literal is matched
+/// if the input continues into it. Unlike re-running every literal's parser on every byte, matching a byte
+/// descends exactly one trie edge, so the cost of a step is proportional to the length of the
+/// common prefix rather than the number of choices.
+#[derive(Debug, Clone)]
+pub struct LiteralSetParser {
+    nodes: Vec<TrieNode>,
+    labels: Vec<String>,
+}
+
+impl LiteralSetParser {
+    /// Build a new parser that chooses between the given literals, returning the index of the
+    /// literal (in `literals` order) that was matched
+    pub fn new(literals: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        let mut labels = Vec::new();
+        for literal in literals {
+            let literal = literal.into();
+            insert(&mut nodes, 0, literal.as_bytes(), labels.len());
+            labels.push(literal);
+        }
+        Self { nodes, labels }
+    }
+
+    fn labels_under(&self, node: usize, out: &mut Vec<Cow<'static, str>>) {
+        if let Some(index) = self.nodes[node].terminal {
+            out.push(Cow::Owned(self.labels[index].clone()));
+        }
+        for (_, child) in &self.nodes[node].children {
+            self.labels_under(*child, out);
+        }
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn insert(nodes: &mut Vec<TrieNode>, node_index: usize, bytes: &[u8], literal_index: usize) {
+    if bytes.is_empty() {
+        nodes[node_index].terminal = Some(literal_index);
+        return;
+    }
+    let children = nodes[node_index].children.clone();
+    for (edge_index, (edge, child)) in children.iter().enumerate() {
+        let common = common_prefix_len(edge, bytes);
+        if common == 0 {
+            continue;
+        }
+        if common == edge.len() {
+            insert(nodes, *child, &bytes[common..], literal_index);
+        } else {
+            // split the edge at the common prefix so both literals keep a distinct suffix
+            let split_node = nodes.len();
+            nodes.push(TrieNode {
+                children: vec![(edge[common..].to_vec(), *child)],
+                terminal: None,
+            });
+            nodes[node_index].children[edge_index] = (edge[..common].to_vec(), split_node);
+            insert(nodes, split_node, &bytes[common..], literal_index);
+        }
+        return;
+    }
+    let leaf = nodes.len();
+    nodes.push(TrieNode {
+        children: Vec::new(),
+        terminal: Some(literal_index),
+    });
+    nodes[node_index].children.push((bytes.to_vec(), leaf));
+}
+
+/// The state of the [`LiteralSetParser`] parser: the trie node we are sitting at, how far along
+/// its in-progress edge (if any) we've matched, and the total bytes consumed so far
+#[derive(Debug, Clone)]
+pub struct LiteralSetParserState {
+    node: usize,
+    edge: Option<EdgeState>,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+struct EdgeState {
+    child: usize,
+    consumed: usize,
+}
+
+impl CreateParserState for LiteralSetParser {
+    fn create_parser_state(&self) -> Self::PartialState {
+        LiteralSetParserState {
+            node: 0,
+            edge: None,
+            offset: 0,
+        }
+    }
+}
+
+impl Parser for LiteralSetParser {
+    type Error = ParseError;
+    type Output = usize;
+    type PartialState = LiteralSetParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> Result<ParseResult<'a, Self::PartialState, Self::Output>, Self::Error> {
+        let mut node = state.node;
+        let mut edge = state.edge.clone();
+        let mut offset = state.offset;
+        let mut remaining = input;
+        loop {
+            if let Some(mut e) = edge.take() {
+                let (edge_bytes, child) = self.nodes[node].children[e.child].clone();
+                while e.consumed < edge_bytes.len() {
+                    match remaining.split_first() {
+                        Some((&byte, rest)) => {
+                            if edge_bytes[e.consumed] != byte {
+                                let mut expected = Vec::new();
+                                self.labels_under(child, &mut expected);
+                                return Err(ParseError::new(offset, expected));
+                            }
+                            e.consumed += 1;
+                            offset += 1;
+                            remaining = rest;
+                        }
+                        None => {
+                            let required_next = edge_bytes[e.consumed..].to_vec();
+                            return Ok(ParseResult::Incomplete {
+                                new_state: LiteralSetParserState {
+                                    node,
+                                    edge: Some(e),
+                                    offset,
+                                },
+                                required_next: Cow::Owned(
+                                    String::from_utf8_lossy(&required_next).to_string(),
+                                ),
+                            });
+                        }
+                    }
+                }
+                node = child;
+            }
+
+            // A terminal node that still has live children means this literal is a byte-prefix of
+            // at least one other literal in the set: don't commit to `Finished` here if the
+            // remaining input could still continue down one of those children, or we'd strand the
+            // longer literal's tail bytes as bogus `remaining` for whatever parses next.
+            let terminal = self.nodes[node].terminal;
+
+            match remaining.split_first() {
+                None => {
+                    if self.nodes[node].children.is_empty() {
+                        match terminal {
+                            Some(literal_index) => {
+                                return Ok(ParseResult::Finished {
+                                    result: literal_index,
+                                    remaining,
+                                });
+                            }
+                            None => unreachable!(
+                                "every trie node has a terminal, children, or both"
+                            ),
+                        }
+                    }
+                    // More input could still extend this node's terminal match into a longer
+                    // literal, so don't finish yet. Only force the next bytes when there's a
+                    // single possible continuation and finishing here isn't also valid.
+                    let required_next = match self.nodes[node].children.as_slice() {
+                        [(only_edge, _)] if terminal.is_none() => {
+                            Cow::Owned(String::from_utf8_lossy(only_edge).to_string())
+                        }
+                        _ => Cow::Borrowed(""),
+                    };
+                    return Ok(ParseResult::Incomplete {
+                        new_state: LiteralSetParserState {
+                            node,
+                            edge: None,
+                            offset,
+                        },
+                        required_next,
+                    });
+                }
+                Some((&byte, _)) => {
+                    let child_index = self.nodes[node]
+                        .children
+                        .iter()
+                        .position(|(edge_bytes, _)| edge_bytes.first() == Some(&byte));
+                    match child_index {
+                        Some(child_index) => {
+                            edge = Some(EdgeState {
+                                child: child_index,
+                                consumed: 0,
+                            });
+                        }
+                        None => match terminal {
+                            // The next byte doesn't continue any child literal, so this shorter
+                            // literal is the real match; the rest of `remaining` is for whatever
+                            // parses next.
+                            Some(literal_index) => {
+                                return Ok(ParseResult::Finished {
+                                    result: literal_index,
+                                    remaining,
+                                });
+                            }
+                            None => {
+                                let mut expected = Vec::new();
+                                self.labels_under(node, &mut expected);
+                                return Err(ParseError::new(offset, expected));
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish(parser: &LiteralSetParser, input: &[u8]) -> (usize, usize) {
+        let state = parser.create_parser_state();
+        match parser.parse(&state, input).unwrap() {
+            ParseResult::Finished { result, remaining } => (result, remaining.len()),
+            ParseResult::Incomplete { .. } => panic!("expected a finished parse"),
+        }
+    }
+
+    #[test]
+    fn prefix_collision_picks_longest_match() {
+        // "get" is a byte-prefix of "get_weather"; feeding the full longer literal plus trailing
+        // bytes must not stop early at the "get" terminal.
+        let parser = LiteralSetParser::new(["get", "get_weather", "set"]);
+        let (index, remaining) = finish(&parser, b"get_weather\n");
+        assert_eq!(index, 1);
+        assert_eq!(remaining, 1);
+
+        let (index, remaining) = finish(&parser, b"get\n");
+        assert_eq!(index, 0);
+        assert_eq!(remaining, 1);
+    }
+
+    #[test]
+    fn incremental_feed_across_calls() {
+        let parser = LiteralSetParser::new(["get_weather", "get_time"]);
+        let mut state = parser.create_parser_state();
+        for byte in b"get_w" {
+            state = match parser.parse(&state, std::slice::from_ref(byte)).unwrap() {
+                ParseResult::Incomplete { new_state, .. } => new_state,
+                ParseResult::Finished { .. } => panic!("should not finish before diverging"),
+            };
+        }
+        match parser.parse(&state, b"eather\n").unwrap() {
+            ParseResult::Finished { result, remaining } => {
+                assert_eq!(result, 0);
+                assert_eq!(remaining, b"\n");
+            }
+            ParseResult::Incomplete { .. } => panic!("expected a finished parse"),
+        }
+    }
+
+    #[test]
+    fn unknown_byte_reports_merged_expected_labels() {
+        let parser = LiteralSetParser::new(["get_weather", "get_time"]);
+        let state = parser.create_parser_state();
+        let err = parser.parse(&state, b"get_x").unwrap_err();
+        let expected: Vec<_> = err.expected.iter().map(|s| s.as_ref()).collect();
+        assert_eq!(expected, vec!["get_weather", "get_time"]);
+    }
+}
@@ -10,6 +10,76 @@ mod calculator;
 pub use calculator::*;
 mod document;
 pub use document::*;
+mod schema;
+pub use schema::*;
+mod literal_set;
+pub use literal_set::*;
+mod multi_line;
+pub use multi_line::*;
+mod format;
+pub use format::*;
+
+/// A structured parse failure, reporting where generation diverged from the grammar and what
+/// would have been accepted there instead of collapsing every failure into `()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The byte offset into the input at which parsing diverged from the grammar
+    pub offset: usize,
+    /// The set of inputs that would have been accepted at `offset`
+    pub expected: Vec<Cow<'static, str>>,
+}
+
+impl ParseError {
+    /// Create a new parse error
+    pub fn new(offset: usize, expected: impl IntoIterator<Item = Cow<'static, str>>) -> Self {
+        Self {
+            offset,
+            expected: expected.into_iter().collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at offset {}, expected one of {:?}",
+            self.offset, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An error produced by [`Tool::run`], so a failed tool call doesn't have to be smuggled back as
+/// a fake observation string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolError {
+    /// A human-readable description of what went wrong
+    pub message: String,
+    /// Whether the same action is worth retrying as-is (e.g. a transient network failure),
+    /// as opposed to a failure the model should learn from before trying something else (e.g.
+    /// an invalid argument)
+    pub retryable: bool,
+}
+
+impl ToolError {
+    /// Create a new tool error
+    pub fn new(message: impl Into<String>, retryable: bool) -> Self {
+        Self {
+            message: message.into(),
+            retryable,
+        }
+    }
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ToolError {}
 
 /// A tool that can be used by a [`kalosm_language_model::Model`]
 // TODO: Add example
@@ -21,17 +91,33 @@ pub trait Tool {
     fn input_prompt(&self) -> String;
     /// A description of the tool
     fn description(&self) -> String;
-    /// Run the tool with the given arguments
-    async fn run(&mut self, args: &str) -> String;
+    /// The schema of the arguments this tool accepts. Defaults to a single free-form string,
+    /// passed to [`Tool::run`] as a `serde_json::Value::String`; override this to constrain
+    /// generation to a structured call.
+    fn args_schema(&self) -> ArgumentSchema {
+        ArgumentSchema::String
+    }
+    /// Whether this tool's input can span multiple lines (see [`MultiLine`]) rather than being
+    /// restricted to the single line [`OneLine`] captures. Defaults to `false`.
+    fn wants_multiline_input(&self) -> bool {
+        false
+    }
+    /// Run the tool with the given arguments, parsed according to [`Tool::args_schema`]
+    async fn run(&mut self, args: serde_json::Value) -> Result<String, ToolError>;
 }
 
-/// A set of tools that can be used by a [`kalosm_language_model::Model`]
+/// A set of tools that can be used by a [`kalosm_language_model::Model`], paired with an
+/// [`AgentFormat`] that renders the prompt and constrains each step of generation. Defaults to
+/// [`ReActFormat`] so existing callers that never mention a format keep working unchanged; swap
+/// in [`JsonFunctionFormat`] (or your own [`AgentFormat`] impl) with [`ToolManager::with_format`]
+/// to target a different prompting scheme without reimplementing the tool-loop plumbing.
 #[derive(Default)]
-pub struct ToolManager {
+pub struct ToolManager<F: AgentFormat = ReActFormat> {
     tools: Vec<Box<dyn Tool + Send + Sync>>,
+    format: F,
 }
 
-impl std::fmt::Debug for ToolManager {
+impl<F: AgentFormat> std::fmt::Debug for ToolManager<F> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ToolManager")
             .field(
@@ -42,17 +128,33 @@ impl std::fmt::Debug for ToolManager {
     }
 }
 
-impl ToolManager {
-    /// Create a new tool empty manager
+impl<F: AgentFormat + Default> ToolManager<F> {
+    /// Create a new empty tool manager using the default-constructed `F` format
     pub fn new() -> Self {
-        Self { tools: Vec::new() }
+        Self {
+            tools: Vec::new(),
+            format: F::default(),
+        }
+    }
+}
+
+impl<F: AgentFormat> ToolManager<F> {
+    /// Create a new empty tool manager using the given format
+    pub fn with_format(format: F) -> Self {
+        Self {
+            tools: Vec::new(),
+            format,
+        }
     }
 
     /// Add a tool to the manager
     pub fn with_tool(self, tool: impl Tool + Send + Sync + 'static) -> Self {
         let mut tools = self.tools;
         tools.push(Box::new(tool));
-        Self { tools }
+        Self {
+            tools,
+            format: self.format,
+        }
     }
 
     /// Add a tool to the manager
@@ -93,8 +195,21 @@ impl ToolManager {
         }
     }
 
-    /// Get a prompt for the tools in the manager
+    /// Get a prompt for the tools in the manager, rendered by this manager's [`AgentFormat`]
     pub fn prompt(&self, question: impl std::fmt::Display) -> String {
+        self.format.prompt(self, question)
+    }
+
+    /// Get the parser that constrains a single step of generation, built by this manager's
+    /// [`AgentFormat`]
+    pub fn step_constraints(&self) -> F::StepParser {
+        self.format.step_constraints(self)
+    }
+
+    /// Render the classic ReAct prompt (`Thought:`/`Action:`/`Input:`/`Final Answer:`) for the
+    /// tools in the manager. [`ReActFormat::prompt`] delegates here; call it directly if you want
+    /// the ReAct template regardless of the manager's configured format.
+    pub fn react_prompt(&self, question: impl std::fmt::Display) -> String {
         let mut tools = String::new();
         let mut tool_names = String::new();
         for tool in self.tools.iter() {
@@ -124,37 +239,65 @@ Question: {question}
         )
     }
 
+    /// Render a [`Tool::run`] result as the `Observation:` line fed back into the prompt,
+    /// distinguishing a successful run from a [`ToolError`] so a driver can decide whether to
+    /// retry the same action (see [`ToolError::retryable`]) instead of moving on.
+    pub fn observation(result: &Result<String, ToolError>) -> String {
+        match result {
+            Ok(output) => format!("Observation: {output}"),
+            Err(error) => format!("Observation: error: {error}"),
+        }
+    }
+
     /// Get the constraints for the tools in the manager
+    ///
+    /// This is backed by a [`LiteralSetParser`], which compiles the tool names and prompts into
+    /// a radix trie once instead of re-running every tool's literal parser on every byte.
     pub fn tool_choices(
         &self,
     ) -> Option<
-        impl Parser<
-                Error = (),
-                Output = usize,
-                PartialState = IndexParserState<LiteralParserOffset, ()>,
-            > + CreateParserState
+        impl Parser<Error = ParseError, Output = usize, PartialState = LiteralSetParserState>
+            + CreateParserState
             + Send
             + Sync
             + 'static,
     > {
-        let mut choices: Vec<LiteralParser<_>> = Vec::with_capacity(self.tools.len());
-        for tool in self.tools.iter() {
-            let name = tool.name();
-            let prompt = tool.input_prompt();
-            choices.push(LiteralParser::from(format!("{name}\n{prompt}")));
-        }
-        if choices.is_empty() {
-            None
-        } else {
-            Some(IndexParser { parsers: choices })
+        if self.tools.is_empty() {
+            return None;
         }
+        let choices = self
+            .tools
+            .iter()
+            .map(|tool| format!("{}\n{}", tool.name(), tool.input_prompt()));
+        Some(LiteralSetParser::new(choices))
+    }
+
+    /// Get the constraints for a tool's arguments, compiled from its [`ArgumentSchema`]
+    pub fn tool_argument_constraints(&self, tool_index: usize) -> Option<ArgumentParser> {
+        self.tools
+            .get(tool_index)
+            .map(|tool| ArgumentParser::new(tool.args_schema()))
+    }
+
+    /// Get the constraints for a tool's input, once its index has been resolved by
+    /// [`ToolManager::tool_choices`]. Tools that opt in with
+    /// [`Tool::wants_multiline_input`] get a [`MultiLine`] constraint instead of the
+    /// single-line default.
+    pub fn input_constraints(&self, tool_index: usize) -> Option<InputConstraint> {
+        self.tools.get(tool_index).map(|tool| {
+            if tool.wants_multiline_input() {
+                InputConstraint::Multi(MultiLine::fenced())
+            } else {
+                InputConstraint::Line(OneLine)
+            }
+        })
     }
 
     /// Get the constraints for the thought action
     pub fn thought_constraints(
         &self,
     ) -> impl Parser<
-        Error = Either<(), ()>,
+        Error = Either<(), ParseError>,
         Output = ((), String),
         PartialState = SequenceParserState<LiteralParserOffset, OneLineState, ()>,
     > + CreateParserState
@@ -172,11 +315,8 @@ Question: {question}
     ) -> SequenceParser<
         SequenceParser<
             LiteralParser<&'static str>,
-            impl Parser<
-                    Error = (),
-                    Output = usize,
-                    PartialState = IndexParserState<LiteralParserOffset, ()>,
-                > + CreateParserState
+            impl Parser<Error = ParseError, Output = usize, PartialState = LiteralSetParserState>
+                + CreateParserState
                 + Send
                 + Sync
                 + 'static,
@@ -193,7 +333,7 @@ Question: {question}
     pub fn answer_constraints(
         &self,
     ) -> impl Parser<
-        Error = Either<(), ()>,
+        Error = Either<(), ParseError>,
         Output = ((), String),
         PartialState = SequenceParserState<LiteralParserOffset, OneLineState, ()>,
     > + CreateParserState
@@ -211,7 +351,7 @@ Question: {question}
     ) -> ChoiceParser<
         ChoiceParser<
             impl kalosm_sample::Parser<
-                    Error = kalosm_sample::Either<(), ()>,
+                    Error = kalosm_sample::Either<(), ParseError>,
                     Output = ((), std::string::String),
                     PartialState = SequenceParserState<LiteralParserOffset, OneLineState, ()>,
                 > + CreateParserState
@@ -222,9 +362,9 @@ Question: {question}
                 SequenceParser<
                     LiteralParser<&str>,
                     impl kalosm_sample::Parser<
-                            Error = (),
+                            Error = ParseError,
                             Output = usize,
-                            PartialState = IndexParserState<LiteralParserOffset, ()>,
+                            PartialState = LiteralSetParserState,
                         > + CreateParserState
                         + Send
                         + Sync
@@ -234,7 +374,7 @@ Question: {question}
             >,
         >,
         impl kalosm_sample::Parser<
-                Error = kalosm_sample::Either<(), ()>,
+                Error = kalosm_sample::Either<(), ParseError>,
                 Output = ((), std::string::String),
                 PartialState = SequenceParserState<LiteralParserOffset, OneLineState, ()>,
             > + CreateParserState
@@ -248,133 +388,8 @@ Question: {question}
     }
 }
 
-/// The state of the [`IndexParser`] parser
-#[derive(Debug, Clone)]
-pub struct IndexParserState<PA, E> {
-    states: Vec<Result<PA, E>>,
-}
-
-/// A parser that parses a sequence of parsers and returns the index of the first parser that succeeds
-pub struct IndexParser<S: Parser<Error = E, Output = (), PartialState = PA>, E, PA> {
-    parsers: Vec<S>,
-}
-
-impl<S: Parser<Error = E, Output = (), PartialState = PA>, E, PA> IndexParser<S, E, PA> {
-    /// Create a new index parser
-    pub fn new(parsers: Vec<S>) -> Self {
-        Self { parsers }
-    }
-}
-
-impl<S, E, PA> CreateParserState for IndexParser<S, E, PA>
-where
-    S: Parser<Error = E, Output = (), PartialState = PA> + CreateParserState,
-    E: Clone,
-    PA: Clone,
-{
-    fn create_parser_state(&self) -> Self::PartialState {
-        IndexParserState {
-            states: self
-                .parsers
-                .iter()
-                .map(|s| Ok(s.create_parser_state()))
-                .collect(),
-        }
-    }
-}
-
-impl<S, E, PA> Parser for IndexParser<S, E, PA>
-where
-    S: Parser<Error = E, Output = (), PartialState = PA>,
-    E: Clone,
-    PA: Clone,
-{
-    type Error = E;
-    type Output = usize;
-    type PartialState = IndexParserState<PA, E>;
-
-    fn parse<'a>(
-        &self,
-        state: &Self::PartialState,
-        input: &'a [u8],
-    ) -> Result<kalosm_sample::ParseResult<'a, Self::PartialState, Self::Output>, Self::Error>
-    where
-        Self: Sized,
-    {
-        let mut states = state.states.clone();
-        let mut has_incomplete_option = false;
-        let mut required_next: Option<Cow<'static, str>> = None;
-        let last_index = self.parsers.len() - 1;
-        for (i, parser) in self.parsers.iter().enumerate() {
-            match &states[i] {
-                Ok(state) => {
-                    let result = parser.parse(state, input);
-                    match result {
-                        Ok(ParseResult::Finished {
-                            result: _,
-                            remaining: r,
-                        }) => {
-                            return Ok(ParseResult::Finished {
-                                result: i,
-                                remaining: r,
-                            })
-                        }
-                        Ok(ParseResult::Incomplete {
-                            new_state: s,
-                            required_next: new_required_next,
-                        }) => {
-                            states[i] = Ok(s);
-                            has_incomplete_option = true;
-                            match required_next {
-                                Some(r) => {
-                                    let mut common_bytes = 0;
-                                    for (byte1, byte2) in r.bytes().zip(new_required_next.bytes()) {
-                                        if byte1 != byte2 {
-                                            break;
-                                        }
-                                        common_bytes += 1;
-                                    }
-                                    required_next = Some(match (r, new_required_next) {
-                                        (Cow::Borrowed(required_next), _) => {
-                                            Cow::Borrowed(&required_next[common_bytes..])
-                                        }
-                                        (_, Cow::Borrowed(required_next)) => {
-                                            Cow::Borrowed(&required_next[common_bytes..])
-                                        }
-                                        (Cow::Owned(mut required_next), _) => {
-                                            required_next.truncate(common_bytes);
-                                            Cow::Owned(required_next)
-                                        }
-                                    });
-                                }
-                                None => {
-                                    required_next = Some(new_required_next);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if !has_incomplete_option && i == last_index {
-                                return Err(e);
-                            }
-                            states[i] = Err(e);
-                        }
-                    }
-                }
-                Err(err) => {
-                    if !has_incomplete_option && i == last_index {
-                        return Err(err.clone());
-                    }
-                }
-            }
-        }
-        Ok(ParseResult::Incomplete {
-            new_state: IndexParserState { states },
-            required_next: required_next.unwrap_or_default(),
-        })
-    }
-}
-
 /// One line of text with some non-whitespace characters
+#[derive(Debug, Clone)]
 pub struct OneLine;
 
 /// The state of the [`OneLine`] parser
@@ -382,6 +397,7 @@ pub struct OneLine;
 pub struct OneLineState {
     all_whitespace: bool,
     bytes: Vec<u8>,
+    offset: usize,
 }
 
 impl CreateParserState for OneLine {
@@ -389,12 +405,13 @@ impl CreateParserState for OneLine {
         OneLineState {
             all_whitespace: true,
             bytes: Vec::new(),
+            offset: 0,
         }
     }
 }
 
 impl Parser for OneLine {
-    type Error = ();
+    type Error = ParseError;
     type Output = String;
     type PartialState = OneLineState;
 
@@ -408,7 +425,10 @@ impl Parser for OneLine {
     {
         if input.is_empty() {
             if state.all_whitespace {
-                return Err(());
+                return Err(ParseError::new(
+                    state.offset,
+                    [Cow::Borrowed("non-empty line")],
+                ));
             } else {
                 return Ok(ParseResult::Incomplete {
                     new_state: state.clone(),
@@ -428,7 +448,10 @@ impl Parser for OneLine {
             }
             if c == b'\n' || c == b'\r' {
                 if state.all_whitespace {
-                    return Err(());
+                    return Err(ParseError::new(
+                        state.offset,
+                        [Cow::Borrowed("non-empty line")],
+                    ));
                 } else {
                     return Ok(ParseResult::Finished {
                         result: String::from_utf8_lossy(&state.bytes).to_string(),
@@ -437,6 +460,7 @@ impl Parser for OneLine {
                 }
             }
             state.bytes.push(c);
+            state.offset += 1;
         }
         Ok(ParseResult::Incomplete {
             new_state: state,
@@ -448,7 +472,7 @@ impl Parser for OneLine {
 macro_rules! impl_from_tool_tuple {
     ($($name:ident),*) => {
         #[allow(non_snake_case)]
-        impl<$($name: Tool + Send + Sync + 'static),*> From<($($name,)*)> for ToolManager {
+        impl<$($name: Tool + Send + Sync + 'static),*> From<($($name,)*)> for ToolManager<ReActFormat> {
             fn from(tools: ($($name,)*)) -> Self {
                 let ($($name,)*) = tools;
                 Self::new()$(.with_tool($name))*
@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+
+use kalosm_sample::{CreateParserState, ParseResult, Parser};
+
+use super::{OneLine, OneLineState, ParseError};
+
+/// How a [`MultiLine`] parser recognizes the end of its input
+#[derive(Debug, Clone, PartialEq)]
+enum MultiLineTerminator {
+    /// Terminated by a closing fence (an opening ` ``` `, optionally followed by a language tag,
+    /// and a matching closing ` ``` ` on its own line)
+    Fence,
+    /// Terminated by a sentinel line that must appear alone on its own line
+    Sentinel(Cow<'static, str>),
+}
+
+/// A parser that accepts input spanning several lines, for tools that take a multi-line payload
+/// (a code interpreter, a SQL runner, a document editor) instead of the single line [`OneLine`]
+/// allows.
+///
+/// [`OneLine`]: super::OneLine
+#[derive(Debug, Clone)]
+pub struct MultiLine {
+    terminator: MultiLineTerminator,
+}
+
+impl MultiLine {
+    /// Accept input delimited by a fenced code block, e.g.
+    ///
+    /// ```text
+    /// ```python
+    /// print("hi")
+    /// ```
+    /// ```
+    pub fn fenced() -> Self {
+        Self {
+            terminator: MultiLineTerminator::Fence,
+        }
+    }
+
+    /// Accept input terminated by a sentinel line, such as `<<END>>` on its own line
+    pub fn sentinel(sentinel: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            terminator: MultiLineTerminator::Sentinel(sentinel.into()),
+        }
+    }
+}
+
+/// The state of the [`MultiLine`] parser
+#[derive(Debug, Clone)]
+pub struct MultiLineState {
+    /// For fenced input, whether the opening fence still needs to be matched
+    awaiting_open: bool,
+    current_line: Vec<u8>,
+    body: Vec<u8>,
+    offset: usize,
+}
+
+fn strip_trailing_newline(line: &[u8]) -> &[u8] {
+    match line {
+        [rest @ .., b'\n'] => rest,
+        _ => line,
+    }
+}
+
+impl CreateParserState for MultiLine {
+    fn create_parser_state(&self) -> Self::PartialState {
+        MultiLineState {
+            awaiting_open: matches!(self.terminator, MultiLineTerminator::Fence),
+            current_line: Vec::new(),
+            body: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl Parser for MultiLine {
+    type Error = ParseError;
+    type Output = String;
+    type PartialState = MultiLineState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> Result<ParseResult<'a, Self::PartialState, Self::Output>, Self::Error> {
+        let mut state = state.clone();
+        let mut iter = input.iter();
+        while let Some(&byte) = iter.next() {
+            // The opening fence's three backticks are forced byte-by-byte, the same way
+            // `LiteralParser` constrains generation; a mismatch here is rejected immediately
+            // instead of being buffered until the line ends.
+            if state.awaiting_open && state.current_line.len() < 3 {
+                if byte != b'`' {
+                    return Err(ParseError::new(state.offset, [Cow::Borrowed("```")]));
+                }
+                state.current_line.push(byte);
+                state.offset += 1;
+                continue;
+            }
+            state.current_line.push(byte);
+            state.offset += 1;
+            if byte != b'\n' {
+                continue;
+            }
+            if state.awaiting_open {
+                // The three backticks are already confirmed; the rest of the line (an optional
+                // language tag) is free-form.
+                state.awaiting_open = false;
+                state.current_line.clear();
+                continue;
+            }
+            let line = strip_trailing_newline(&state.current_line);
+            let closes = match &self.terminator {
+                MultiLineTerminator::Fence => line == b"```",
+                MultiLineTerminator::Sentinel(sentinel) => line == sentinel.as_bytes(),
+            };
+            if closes {
+                return Ok(ParseResult::Finished {
+                    result: String::from_utf8_lossy(&state.body).to_string(),
+                    remaining: iter.as_slice(),
+                });
+            }
+            state.body.extend_from_slice(&state.current_line);
+            state.current_line.clear();
+        }
+        let required_next = if state.awaiting_open {
+            let matched = state.current_line.len().min(3);
+            Cow::Owned(String::from_utf8_lossy(&b"```"[matched..]).to_string())
+        } else {
+            Default::default()
+        };
+        Ok(ParseResult::Incomplete {
+            new_state: state,
+            required_next,
+        })
+    }
+}
+
+/// The constraint on a tool's input: either a single line, or a [`MultiLine`] payload for tools
+/// that opted in with [`Tool::wants_multiline_input`](super::Tool::wants_multiline_input)
+#[derive(Debug, Clone)]
+pub enum InputConstraint {
+    /// A single line of input
+    Line(OneLine),
+    /// A multi-line input, terminated by a fence or sentinel
+    Multi(MultiLine),
+}
+
+/// The state of the [`InputConstraint`] parser
+#[derive(Debug, Clone)]
+pub enum InputConstraintState {
+    /// See [`InputConstraint::Line`]
+    Line(OneLineState),
+    /// See [`InputConstraint::Multi`]
+    Multi(MultiLineState),
+}
+
+impl CreateParserState for InputConstraint {
+    fn create_parser_state(&self) -> Self::PartialState {
+        match self {
+            Self::Line(parser) => InputConstraintState::Line(parser.create_parser_state()),
+            Self::Multi(parser) => InputConstraintState::Multi(parser.create_parser_state()),
+        }
+    }
+}
+
+impl Parser for InputConstraint {
+    type Error = ParseError;
+    type Output = String;
+    type PartialState = InputConstraintState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> Result<ParseResult<'a, Self::PartialState, Self::Output>, Self::Error> {
+        match (self, state) {
+            (Self::Line(parser), InputConstraintState::Line(state)) => parser
+                .parse(state, input)
+                .map(|result| map_state(result, InputConstraintState::Line)),
+            (Self::Multi(parser), InputConstraintState::Multi(state)) => parser
+                .parse(state, input)
+                .map(|result| map_state(result, InputConstraintState::Multi)),
+            _ => unreachable!("InputConstraintState always matches the InputConstraint variant it was created from"),
+        }
+    }
+}
+
+fn map_state<'a, S, T>(
+    result: ParseResult<'a, S, String>,
+    wrap: impl FnOnce(S) -> T,
+) -> ParseResult<'a, T, String> {
+    match result {
+        ParseResult::Finished { result, remaining } => ParseResult::Finished { result, remaining },
+        ParseResult::Incomplete {
+            new_state,
+            required_next,
+        } => ParseResult::Incomplete {
+            new_state: wrap(new_state),
+            required_next,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fenced_opening_backtick_forced_byte_by_byte() {
+        let parser = MultiLine::fenced();
+        let mut state = parser.create_parser_state();
+        for (byte, expected_remaining) in [(b'`', "``"), (b'`', "`"), (b'`', "")] {
+            state = match parser.parse(&state, &[byte]).unwrap() {
+                ParseResult::Incomplete {
+                    new_state,
+                    required_next,
+                } => {
+                    assert_eq!(required_next, expected_remaining);
+                    new_state
+                }
+                ParseResult::Finished { .. } => panic!("should not finish mid-fence"),
+            };
+        }
+    }
+
+    #[test]
+    fn fenced_opening_fence_rejects_wrong_byte_immediately() {
+        let parser = MultiLine::fenced();
+        let state = parser.create_parser_state();
+        assert!(parser.parse(&state, b"``x").is_err());
+    }
+
+    #[test]
+    fn fenced_incremental_feed_across_calls() {
+        let parser = MultiLine::fenced();
+        let mut state = parser.create_parser_state();
+        for chunk in [&b"```python\n"[..], b"print(1)\n", b"```\nrest"] {
+            match parser.parse(&state, chunk).unwrap() {
+                ParseResult::Incomplete { new_state, .. } => state = new_state,
+                ParseResult::Finished { result, remaining } => {
+                    assert_eq!(result, "print(1)\n");
+                    assert_eq!(remaining, b"rest");
+                    return;
+                }
+            }
+        }
+        panic!("expected the fence to close before input ran out");
+    }
+}
@@ -0,0 +1,695 @@
+use std::borrow::Cow;
+
+use kalosm_sample::{CreateParserState, ParseResult, Parser};
+use serde_json::Value;
+
+/// The shape of the arguments a [`super::Tool`] accepts.
+///
+/// A [`ArgumentSchema`] is compiled into an [`ArgumentParser`] that constrains generation to
+/// syntactically valid JSON matching the shape, so a tool never has to guard against malformed
+/// input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgumentSchema {
+    /// A JSON string
+    String,
+    /// A JSON number
+    Number,
+    /// A JSON boolean
+    Bool,
+    /// A JSON array where every element matches the inner schema
+    Array(Box<ArgumentSchema>),
+    /// A JSON object with a fixed, ordered set of named fields
+    Object(Vec<(Cow<'static, str>, ArgumentSchema)>),
+    /// A value that may be omitted from its containing object
+    Optional(Box<ArgumentSchema>),
+}
+
+impl ArgumentSchema {
+    /// Create an object schema from a list of `(name, schema)` fields
+    pub fn object(
+        fields: impl IntoIterator<Item = (impl Into<Cow<'static, str>>, ArgumentSchema)>,
+    ) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(name, schema)| (name.into(), schema))
+                .collect(),
+        )
+    }
+
+    /// Wrap this schema to mark it optional
+    pub fn optional(self) -> Self {
+        Self::Optional(Box::new(self))
+    }
+}
+
+/// A [`Parser`] that constrains generation to JSON matching an [`ArgumentSchema`]
+#[derive(Debug, Clone)]
+pub struct ArgumentParser {
+    schema: ArgumentSchema,
+}
+
+impl ArgumentParser {
+    /// Create a new parser that constrains generation to the given schema
+    pub fn new(schema: ArgumentSchema) -> Self {
+        Self { schema }
+    }
+}
+
+impl CreateParserState for ArgumentParser {
+    fn create_parser_state(&self) -> Self::PartialState {
+        ArgumentParserState(value_state_for(&self.schema))
+    }
+}
+
+impl Parser for ArgumentParser {
+    type Error = ();
+    type Output = Value;
+    type PartialState = ArgumentParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> Result<ParseResult<'a, Self::PartialState, Self::Output>, Self::Error> {
+        parse_value(&self.schema, &state.0, input).map(|result| match result {
+            ParseResult::Finished { result, remaining } => {
+                ParseResult::Finished { result, remaining }
+            }
+            ParseResult::Incomplete {
+                new_state,
+                required_next,
+            } => ParseResult::Incomplete {
+                new_state: ArgumentParserState(new_state),
+                required_next,
+            },
+        })
+    }
+}
+
+/// The state of the [`ArgumentParser`] parser
+#[derive(Debug, Clone)]
+pub struct ArgumentParserState(ValueState);
+
+/// The in-progress state of a single JSON value being parsed against an [`ArgumentSchema`]
+#[derive(Debug, Clone)]
+enum ValueState {
+    String(StringState),
+    Number(NumberState),
+    Bool { matched: usize },
+    Array(ArrayState),
+    Object(ObjectState),
+    Optional(Box<ValueState>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct StringState {
+    started: bool,
+    escaped: bool,
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct NumberState {
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct ArrayState {
+    items: Vec<Value>,
+    current: Box<ValueState>,
+    started: bool,
+    expect_comma_or_close: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ObjectState {
+    /// Whether the opening `{` has been matched yet
+    started: bool,
+    next_field: usize,
+    values: Vec<(Cow<'static, str>, Value)>,
+    current: Box<ValueState>,
+    started_field: bool,
+}
+
+fn value_state_for(schema: &ArgumentSchema) -> ValueState {
+    match schema {
+        ArgumentSchema::String => ValueState::String(StringState::default()),
+        ArgumentSchema::Number => ValueState::Number(NumberState::default()),
+        ArgumentSchema::Bool => ValueState::Bool { matched: 0 },
+        ArgumentSchema::Array(element) => ValueState::Array(ArrayState {
+            items: Vec::new(),
+            current: Box::new(value_state_for(element)),
+            started: false,
+            expect_comma_or_close: false,
+        }),
+        ArgumentSchema::Object(fields) => ValueState::Object(ObjectState {
+            started: false,
+            next_field: 0,
+            values: Vec::new(),
+            current: Box::new(
+                fields
+                    .first()
+                    .map(|(_, schema)| value_state_for(schema))
+                    .unwrap_or(ValueState::Bool { matched: 0 }),
+            ),
+            started_field: false,
+        }),
+        ArgumentSchema::Optional(inner) => ValueState::Optional(Box::new(value_state_for(inner))),
+    }
+}
+
+type ValueParseResult<'a> = Result<ParseResult<'a, ValueState, Value>, ()>;
+
+fn parse_value<'a>(
+    schema: &ArgumentSchema,
+    state: &ValueState,
+    input: &'a [u8],
+) -> ValueParseResult<'a> {
+    match (schema, state) {
+        (ArgumentSchema::String, ValueState::String(s)) => parse_string(s, input),
+        (ArgumentSchema::Number, ValueState::Number(s)) => parse_number(s, input),
+        (ArgumentSchema::Bool, ValueState::Bool { matched }) => parse_bool(*matched, input),
+        (ArgumentSchema::Array(element), ValueState::Array(s)) => parse_array(element, s, input),
+        (ArgumentSchema::Object(fields), ValueState::Object(s)) => parse_object(fields, s, input),
+        (ArgumentSchema::Optional(inner), ValueState::Optional(s)) => {
+            parse_optional(inner, s, input)
+        }
+        _ => Err(()),
+    }
+}
+
+fn parse_string<'a>(state: &StringState, input: &'a [u8]) -> ValueParseResult<'a> {
+    let mut state = state.clone();
+    let mut iter = input.iter();
+    if !state.started {
+        match iter.next() {
+            Some(b'"') => state.started = true,
+            Some(_) | None if input.is_empty() => {
+                return Ok(ParseResult::Incomplete {
+                    new_state: ValueState::String(state),
+                    required_next: Cow::Borrowed("\""),
+                })
+            }
+            _ => return Err(()),
+        }
+    }
+    for &byte in iter.by_ref() {
+        if state.escaped {
+            state.bytes.push(byte);
+            state.escaped = false;
+            continue;
+        }
+        match byte {
+            b'\\' => state.escaped = true,
+            b'"' => {
+                let value = String::from_utf8_lossy(&state.bytes).to_string();
+                return Ok(ParseResult::Finished {
+                    result: Value::String(value),
+                    remaining: iter.as_slice(),
+                });
+            }
+            _ => state.bytes.push(byte),
+        }
+    }
+    Ok(ParseResult::Incomplete {
+        new_state: ValueState::String(state),
+        required_next: Default::default(),
+    })
+}
+
+fn parse_number<'a>(state: &NumberState, input: &'a [u8]) -> ValueParseResult<'a> {
+    let mut state = state.clone();
+    let mut consumed = 0;
+    for &byte in input {
+        let valid = byte.is_ascii_digit()
+            || (byte == b'-' && state.bytes.is_empty())
+            || (byte == b'.' && !state.bytes.contains(&b'.'));
+        if !valid {
+            break;
+        }
+        state.bytes.push(byte);
+        consumed += 1;
+    }
+    let remaining = &input[consumed..];
+    if remaining.is_empty() {
+        Ok(ParseResult::Incomplete {
+            new_state: ValueState::Number(state),
+            required_next: Default::default(),
+        })
+    } else if state.bytes.is_empty() || state.bytes == b"-" {
+        Err(())
+    } else {
+        let text = String::from_utf8_lossy(&state.bytes);
+        let number = text.parse::<f64>().map_err(|_| ())?;
+        let value = serde_json::Number::from_f64(number)
+            .map(Value::Number)
+            .unwrap_or(Value::Null);
+        Ok(ParseResult::Finished {
+            result: value,
+            remaining,
+        })
+    }
+}
+
+fn parse_bool<'a>(matched: usize, input: &'a [u8]) -> ValueParseResult<'a> {
+    const TRUE: &[u8] = b"true";
+    const FALSE: &[u8] = b"false";
+    if input.is_empty() {
+        return Ok(ParseResult::Incomplete {
+            new_state: ValueState::Bool { matched },
+            required_next: Default::default(),
+        });
+    }
+    let literal: &[u8] = if matched == 0 {
+        match input[0] {
+            b't' => TRUE,
+            b'f' => FALSE,
+            _ => return Err(()),
+        }
+    } else if TRUE.get(matched) == Some(&input[0]) {
+        TRUE
+    } else {
+        FALSE
+    };
+    let mut matched = matched;
+    let mut iter = input.iter();
+    for &byte in iter.by_ref() {
+        if matched >= literal.len() || literal[matched] != byte {
+            return Err(());
+        }
+        matched += 1;
+        if matched == literal.len() {
+            return Ok(ParseResult::Finished {
+                result: Value::Bool(literal == TRUE),
+                remaining: iter.as_slice(),
+            });
+        }
+    }
+    Ok(ParseResult::Incomplete {
+        new_state: ValueState::Bool { matched },
+        required_next: Cow::Owned(String::from_utf8_lossy(&literal[matched..]).to_string()),
+    })
+}
+
+fn parse_array<'a>(
+    element: &ArgumentSchema,
+    state: &ArrayState,
+    input: &'a [u8],
+) -> ValueParseResult<'a> {
+    let mut state = state.clone();
+    let mut remaining = input;
+    loop {
+        if !state.started {
+            match remaining.split_first() {
+                Some((b'[', rest)) => {
+                    state.started = true;
+                    remaining = rest;
+                }
+                _ if remaining.is_empty() => {
+                    return Ok(ParseResult::Incomplete {
+                        new_state: ValueState::Array(state),
+                        required_next: Cow::Borrowed("["),
+                    })
+                }
+                _ => return Err(()),
+            }
+        }
+        if state.expect_comma_or_close {
+            match remaining.split_first() {
+                Some((b']', rest)) => {
+                    return Ok(ParseResult::Finished {
+                        result: Value::Array(state.items),
+                        remaining: rest,
+                    })
+                }
+                Some((b',', rest)) => {
+                    state.expect_comma_or_close = false;
+                    state.current = Box::new(value_state_for(element));
+                    remaining = rest;
+                }
+                _ if remaining.is_empty() => {
+                    return Ok(ParseResult::Incomplete {
+                        new_state: ValueState::Array(state),
+                        required_next: Default::default(),
+                    })
+                }
+                _ => return Err(()),
+            }
+            continue;
+        }
+        if state.items.is_empty() {
+            // allow an immediate `]` for an empty array, instead of delegating to `parse_value`,
+            // which would reject `]` as an invalid start for every element schema
+            match remaining.split_first() {
+                Some((b']', rest)) => {
+                    return Ok(ParseResult::Finished {
+                        result: Value::Array(state.items),
+                        remaining: rest,
+                    })
+                }
+                None => {
+                    return Ok(ParseResult::Incomplete {
+                        new_state: ValueState::Array(state),
+                        required_next: Default::default(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        match parse_value(element, &state.current, remaining)? {
+            ParseResult::Finished {
+                result,
+                remaining: rest,
+            } => {
+                state.items.push(result);
+                state.expect_comma_or_close = true;
+                remaining = rest;
+            }
+            ParseResult::Incomplete {
+                new_state,
+                required_next,
+            } => {
+                state.current = Box::new(new_state);
+                return Ok(ParseResult::Incomplete {
+                    new_state: ValueState::Array(state),
+                    required_next,
+                });
+            }
+        }
+    }
+}
+
+fn parse_object<'a>(
+    fields: &[(Cow<'static, str>, ArgumentSchema)],
+    state: &ObjectState,
+    input: &'a [u8],
+) -> ValueParseResult<'a> {
+    let mut state = state.clone();
+    let mut remaining = input;
+    if !state.started {
+        match remaining.split_first() {
+            Some((b'{', rest)) => {
+                state.started = true;
+                remaining = rest;
+            }
+            _ if remaining.is_empty() => {
+                return Ok(ParseResult::Incomplete {
+                    new_state: ValueState::Object(state),
+                    required_next: Cow::Borrowed("{"),
+                })
+            }
+            _ => return Err(()),
+        }
+    }
+    if fields.is_empty() {
+        return match remaining.split_first() {
+            Some((b'}', rest)) => Ok(ParseResult::Finished {
+                result: Value::Object(serde_json::Map::new()),
+                remaining: rest,
+            }),
+            _ if remaining.is_empty() => Ok(ParseResult::Incomplete {
+                new_state: ValueState::Object(state),
+                required_next: Cow::Borrowed("}"),
+            }),
+            _ => Err(()),
+        };
+    }
+    loop {
+        if state.next_field >= fields.len() {
+            return match remaining.split_first() {
+                Some((b'}', rest)) => {
+                    let mut object = serde_json::Map::new();
+                    for (name, value) in state.values {
+                        object.insert(name.into_owned(), value);
+                    }
+                    Ok(ParseResult::Finished {
+                        result: Value::Object(object),
+                        remaining: rest,
+                    })
+                }
+                _ if remaining.is_empty() => Ok(ParseResult::Incomplete {
+                    new_state: ValueState::Object(state),
+                    required_next: Cow::Borrowed("}"),
+                }),
+                _ => Err(()),
+            };
+        }
+        let (name, schema) = &fields[state.next_field];
+        let is_optional = matches!(schema, ArgumentSchema::Optional(_));
+        if !state.started_field {
+            let key = format!("\"{name}\":");
+            let key_bytes = key.as_bytes();
+            let prefix_len = remaining.len().min(key_bytes.len());
+            let key_matches = &remaining[..prefix_len] == &key_bytes[..prefix_len];
+            if !key_matches {
+                if is_optional {
+                    // This field wasn't provided; move on to whatever comes next (the next
+                    // field's key, or the closing brace) without consuming anything.
+                    state.next_field += 1;
+                    state.current = Box::new(
+                        fields
+                            .get(state.next_field)
+                            .map(|(_, schema)| value_state_for(schema))
+                            .unwrap_or(ValueState::Bool { matched: 0 }),
+                    );
+                    continue;
+                }
+                return Err(());
+            }
+            remaining = &remaining[prefix_len..];
+            if prefix_len < key_bytes.len() {
+                if is_optional {
+                    // The bytes seen so far are consistent with this field's key, but there
+                    // isn't enough input yet to tell whether it'll keep matching or diverge
+                    // (meaning the field was omitted); don't force the rest of an optional
+                    // field's key the way a required field's key is forced below.
+                    return Ok(ParseResult::Incomplete {
+                        new_state: ValueState::Object(state),
+                        required_next: Default::default(),
+                    });
+                }
+                return Ok(ParseResult::Incomplete {
+                    new_state: ValueState::Object(state),
+                    required_next: Cow::Owned(
+                        String::from_utf8_lossy(&key_bytes[prefix_len..]).to_string(),
+                    ),
+                });
+            }
+            state.started_field = true;
+        }
+        match parse_value(schema, &state.current, remaining)? {
+            ParseResult::Finished {
+                result,
+                remaining: rest,
+            } => {
+                state.values.push((name.clone(), result));
+                state.next_field += 1;
+                state.started_field = false;
+                state.current = Box::new(
+                    fields
+                        .get(state.next_field)
+                        .map(|(_, schema)| value_state_for(schema))
+                        .unwrap_or(ValueState::Bool { matched: 0 }),
+                );
+                remaining = if state.next_field < fields.len() {
+                    // the object may close here when every field left is optional
+                    let rest_optional = fields[state.next_field..]
+                        .iter()
+                        .all(|(_, schema)| matches!(schema, ArgumentSchema::Optional(_)));
+                    match rest.split_first() {
+                        Some((b',', after_comma)) => after_comma,
+                        Some((b'}', _)) if rest_optional => {
+                            state.next_field = fields.len();
+                            rest
+                        }
+                        _ if rest.is_empty() => {
+                            return Ok(ParseResult::Incomplete {
+                                new_state: ValueState::Object(state),
+                                required_next: if rest_optional {
+                                    Default::default()
+                                } else {
+                                    Cow::Borrowed(",")
+                                },
+                            })
+                        }
+                        _ => return Err(()),
+                    }
+                } else {
+                    rest
+                };
+            }
+            ParseResult::Incomplete {
+                new_state,
+                required_next,
+            } => {
+                state.current = Box::new(new_state);
+                return Ok(ParseResult::Incomplete {
+                    new_state: ValueState::Object(state),
+                    required_next,
+                });
+            }
+        }
+    }
+}
+
+fn parse_optional<'a>(
+    inner: &ArgumentSchema,
+    state: &ValueState,
+    input: &'a [u8],
+) -> ValueParseResult<'a> {
+    match parse_value(inner, state, input) {
+        Ok(ParseResult::Finished { result, remaining }) => {
+            Ok(ParseResult::Finished { result, remaining })
+        }
+        Ok(ParseResult::Incomplete {
+            new_state,
+            required_next,
+        }) => Ok(ParseResult::Incomplete {
+            new_state: ValueState::Optional(Box::new(new_state)),
+            required_next,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish(parser: &ArgumentParser, input: &[u8]) -> (Value, usize) {
+        let state = parser.create_parser_state();
+        match parser.parse(&state, input).unwrap() {
+            ParseResult::Finished { result, remaining } => (result, remaining.len()),
+            ParseResult::Incomplete { .. } => panic!("expected a finished parse"),
+        }
+    }
+
+    #[test]
+    fn two_string_fields_parse_in_one_chunk() {
+        let schema = ArgumentSchema::object([
+            ("a", ArgumentSchema::String),
+            ("b", ArgumentSchema::String),
+        ]);
+        let parser = ArgumentParser::new(schema);
+        let (value, remaining) = finish(&parser, br#"{"a":"x","b":"y"}"#);
+        assert_eq!(
+            value,
+            Value::Object(
+                [
+                    ("a".to_string(), Value::String("x".to_string())),
+                    ("b".to_string(), Value::String("y".to_string())),
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn incremental_feed_splits_mid_string() {
+        let schema = ArgumentSchema::object([
+            ("a", ArgumentSchema::String),
+            ("b", ArgumentSchema::String),
+        ]);
+        let parser = ArgumentParser::new(schema);
+        let state = parser.create_parser_state();
+        let state = match parser.parse(&state, br#"{"a":"x","b":""#).unwrap() {
+            ParseResult::Incomplete { new_state, .. } => new_state,
+            ParseResult::Finished { .. } => panic!("expected incomplete"),
+        };
+        match parser.parse(&state, br#"y"}"#).unwrap() {
+            ParseResult::Finished { result, remaining } => {
+                assert_eq!(
+                    result,
+                    Value::Object(
+                        [
+                            ("a".to_string(), Value::String("x".to_string())),
+                            ("b".to_string(), Value::String("y".to_string())),
+                        ]
+                        .into_iter()
+                        .collect()
+                    )
+                );
+                assert_eq!(remaining.len(), 0);
+            }
+            ParseResult::Incomplete { .. } => panic!("expected a finished parse"),
+        }
+    }
+
+    #[test]
+    fn optional_field_can_be_omitted() {
+        let schema = ArgumentSchema::object([
+            ("a", ArgumentSchema::String),
+            ("b", ArgumentSchema::Number.optional()),
+            ("c", ArgumentSchema::Bool),
+        ]);
+        let parser = ArgumentParser::new(schema);
+        let (value, remaining) = finish(&parser, br#"{"a":"x","c":true}"#);
+        assert_eq!(
+            value,
+            Value::Object(
+                [
+                    ("a".to_string(), Value::String("x".to_string())),
+                    ("c".to_string(), Value::Bool(true)),
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn optional_field_can_still_be_present() {
+        let schema = ArgumentSchema::object([
+            ("a", ArgumentSchema::String),
+            ("b", ArgumentSchema::Number.optional()),
+            ("c", ArgumentSchema::Bool),
+        ]);
+        let parser = ArgumentParser::new(schema);
+        let (value, remaining) = finish(&parser, br#"{"a":"x","b":2,"c":true}"#);
+        assert_eq!(
+            value,
+            Value::Object(
+                [
+                    ("a".to_string(), Value::String("x".to_string())),
+                    (
+                        "b".to_string(),
+                        Value::Number(serde_json::Number::from_f64(2.0).unwrap())
+                    ),
+                    ("c".to_string(), Value::Bool(true)),
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn empty_array_parses() {
+        let schema = ArgumentSchema::Array(Box::new(ArgumentSchema::Number));
+        let parser = ArgumentParser::new(schema);
+        let (value, remaining) = finish(&parser, b"[]");
+        assert_eq!(value, Value::Array(Vec::new()));
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn non_empty_array_parses() {
+        let schema = ArgumentSchema::Array(Box::new(ArgumentSchema::Number));
+        let parser = ArgumentParser::new(schema);
+        let (value, remaining) = finish(&parser, b"[1,2,3]");
+        assert_eq!(
+            value,
+            Value::Array(vec![
+                Value::Number(serde_json::Number::from_f64(1.0).unwrap()),
+                Value::Number(serde_json::Number::from_f64(2.0).unwrap()),
+                Value::Number(serde_json::Number::from_f64(3.0).unwrap()),
+            ])
+        );
+        assert_eq!(remaining, 0);
+    }
+}